@@ -5,23 +5,148 @@ use tokio::prelude::*;
 use tokio::r#await;
 use wire::utils::CompatFix;
 
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{oneshot, watch};
+use tokio::timer::Delay;
+
+use futures::future::{self, Either};
+
+use log::{info, warn};
 
 use stratum::v2::framing::codec::V2Framing;
 use stratum::v2::messages::{
     NewMiningJob, OpenChannel, OpenChannelError, OpenChannelSuccess, SetNewPrevHash, SetTarget,
     SetupMiningConnection, SetupMiningConnectionError, SetupMiningConnectionSuccess, SubmitShares,
+    SubmitSharesError, SubmitSharesSuccess, UpdateChannel,
 };
 use stratum::v2::types::DeviceInfo;
 use stratum::v2::{V2Handler, V2Protocol};
 use wire::{Connection, ConnectionRx, ConnectionTx, Framing, Message};
 
 use bitcoin_hashes::{sha256d::Hash, Hash as HashTrait};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+
+/// The version bits our hardware is able to roll, declared to the pool during connection setup.
+/// The mask a job actually rolls is whatever the pool grants back, which may be a subset of
+/// this (or none at all) - see `StratumEventHandler::version_rolling_mask`.
+const SUPPORTED_VERSION_ROLLING_MASK: u32 = 0x1fffe000;
+
+/// Initial delay before the first reconnect attempt.
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+/// Reconnect backoff never grows past this.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+/// How long `run` stays on a lower-priority pool before giving a higher-priority one another
+/// chance to connect.
+const FAILBACK_PROBE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Consecutive share rejections (with no accept in between) before a pool is considered
+/// unhealthy and `run` fails over to the next one, even though the connection is still up.
+const MAX_CONSECUTIVE_REJECTS: u32 = 10;
+
+/// One entry in the ordered list of pools `run` fails over between, highest priority first.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub address: String,
+    pub user: String,
+    /// `OpenChannel` has no password field, so this currently isn't sent anywhere - kept for
+    /// parity with how pools are usually configured and for protocol extensions that may add it.
+    pub password: Option<String>,
+}
 
-// TODO: move it to the stratum crate
-const VERSION_MASK: u32 = 0x1fffe000;
+/// Tracks which of the configured pools `run` is currently connecting to. Always prefers the
+/// highest-priority one: a failure drops down to the next, and a standing timer periodically
+/// gives up on a lower-priority pool to try failing back to a higher-priority one.
+struct PoolFailover {
+    pools: Vec<PoolConfig>,
+    active: usize,
+    active_since: Instant,
+}
+
+impl PoolFailover {
+    fn new(pools: Vec<PoolConfig>) -> Self {
+        assert!(!pools.is_empty(), "at least one pool must be configured");
+        Self {
+            pools,
+            active: 0,
+            active_since: Instant::now(),
+        }
+    }
+
+    fn current(&self) -> &PoolConfig {
+        &self.pools[self.active]
+    }
+
+    /// Drops down to the next pool in priority order after a failure, wrapping back to the
+    /// highest-priority one once every pool has been tried.
+    fn on_failure(&mut self) {
+        self.active = (self.active + 1) % self.pools.len();
+        self.active_since = Instant::now();
+    }
+
+    /// Whether it's time to give a higher-priority pool another chance.
+    fn should_attempt_failback(&self, now: Instant) -> bool {
+        self.active != 0 && now.duration_since(self.active_since) >= FAILBACK_PROBE_INTERVAL
+    }
+
+    /// Moves back to the highest-priority pool for the next connection attempt.
+    fn probe_failback(&mut self) {
+        self.active = 0;
+        self.active_since = Instant::now();
+    }
+
+    /// When a connection to the current pool should be torn down so `run` can give a
+    /// higher-priority pool another chance, even though nothing is actually wrong with it.
+    /// `None` on the highest-priority pool, since there's nothing higher to fail back to.
+    fn failback_deadline(&self) -> Option<Instant> {
+        if self.active == 0 {
+            None
+        } else {
+            Some(self.active_since + FAILBACK_PROBE_INTERVAL)
+        }
+    }
+}
+
+/// Anything that can end a stratum connection. Most variants are genuine failures: `run()` tears
+/// the connection down and reconnects after a backoff. `FailbackDue` is the odd one out - the
+/// connection is perfectly healthy, but `run()` still tears it down, this time to give a
+/// higher-priority pool another chance (see `PoolFailover::failback_deadline`).
+#[derive(Debug)]
+enum ConnectionError {
+    Io(std::io::Error),
+    SetupRejected,
+    OpenChannelRejected,
+    Protocol(String),
+    /// The pool kept rejecting shares (see `MAX_CONSECUTIVE_REJECTS`) while the connection
+    /// itself stayed up - worth failing over to the next pool even though nothing actually
+    /// broke at the transport level.
+    TooManyRejects,
+    /// Not a failure: the failback probe timer fired while connected to a lower-priority pool,
+    /// so `run` is tearing this connection down on purpose to retry a higher-priority one.
+    FailbackDue,
+}
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::SetupRejected => write!(f, "stratum server rejected setup mining connection"),
+            Self::OpenChannelRejected => write!(f, "stratum server rejected open channel"),
+            Self::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Self::TooManyRejects => write!(f, "too many consecutive share rejections from pool"),
+            Self::FailbackDue => write!(f, "failback probe due for a higher-priority pool"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+impl From<std::io::Error> for ConnectionError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
 
 #[derive(Clone)]
 struct StratumJob {
@@ -35,6 +160,12 @@ struct StratumJob {
     time: u32,
     max_time: u32,
     bits: u32,
+    /// The version-rolling mask negotiated with the pool for this connection, zero if the pool
+    /// doesn't support version rolling.
+    version_mask: u32,
+    /// Cleared by the submit-error handler when the pool reports this specific job as stale,
+    /// so we don't have to wait for the next `SetNewPrevHash` to stop working on it.
+    valid: Arc<AtomicBool>,
 }
 
 impl StratumJob {
@@ -42,6 +173,7 @@ impl StratumJob {
         job_msg: &NewMiningJob,
         prevhash_msg: &SetNewPrevHash,
         current_block_height: Arc<AtomicU32>,
+        version_mask: u32,
     ) -> Self {
         assert_eq!(job_msg.block_height, prevhash_msg.block_height);
         Self {
@@ -55,8 +187,16 @@ impl StratumJob {
             time: prevhash_msg.min_ntime,
             max_time: prevhash_msg.min_ntime + prevhash_msg.max_ntime_offset as u32,
             bits: prevhash_msg.nbits,
+            version_mask,
+            valid: Arc::new(AtomicBool::new(true)),
         }
     }
+
+    /// Marks this job (and every clone sharing its identity, e.g. the one handed to the
+    /// hardware via `workhub`) as no longer worth solving.
+    fn invalidate(&self) {
+        self.valid.store(false, Ordering::Relaxed);
+    }
 }
 
 impl hal::BitcoinJob for StratumJob {
@@ -65,7 +205,7 @@ impl hal::BitcoinJob for StratumJob {
     }
 
     fn version_mask(&self) -> u32 {
-        VERSION_MASK
+        self.version_mask
     }
 
     fn previous_hash(&self) -> &Hash {
@@ -89,24 +229,402 @@ impl hal::BitcoinJob for StratumJob {
     }
 
     fn is_valid(&self) -> bool {
-        self.block_height >= self.current_block_height.load(Ordering::Relaxed)
+        self.valid.load(Ordering::Relaxed)
+            && self.block_height >= self.current_block_height.load(Ordering::Relaxed)
+    }
+}
+
+/// How many shares we track between submission and acknowledgement. Bounds the cost of a pool
+/// that stops responding to submits instead of growing the tracking window without limit.
+const MAX_IN_FLIGHT_SHARES: usize = 256;
+
+/// Sliding window of shares that have been submitted to the pool and are awaiting a
+/// `SubmitSharesSuccess`/`SubmitSharesError` response, keyed by the `seq_num` assigned in
+/// `StratumSolutionHandler::process_solution`.
+struct InFlightShares {
+    jobs: HashMap<u32, StratumJob>,
+    order: VecDeque<u32>,
+}
+
+impl InFlightShares {
+    fn new() -> Self {
+        Self {
+            jobs: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, seq_num: u32, job: StratumJob) {
+        self.jobs.insert(seq_num, job);
+        self.order.push_back(seq_num);
+        while self.order.len() > MAX_IN_FLIGHT_SHARES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.jobs.remove(&oldest);
+            }
+        }
+    }
+
+    /// Removes and returns the job submitted as `seq_num`, if it is still tracked.
+    fn remove(&mut self, seq_num: u32) -> Option<StratumJob> {
+        self.jobs.remove(&seq_num)
+    }
+
+    /// Removes every entry up to and including `seq_num`, as acknowledged by a cumulative
+    /// `SubmitSharesSuccess`, returning the jobs that were submitted for them.
+    fn remove_up_to(&mut self, seq_num: u32) -> Vec<StratumJob> {
+        let mut removed = Vec::new();
+        while let Some(&oldest) = self.order.front() {
+            if oldest > seq_num {
+                break;
+            }
+            self.order.pop_front();
+            if let Some(job) = self.jobs.remove(&oldest) {
+                removed.push(job);
+            }
+        }
+        removed
+    }
+}
+
+/// Shared between `StratumSolutionHandler`, which inserts an entry on every submit, and
+/// `StratumEventHandler`, which clears entries as the pool acknowledges them.
+type SharedInFlightShares = Arc<Mutex<InFlightShares>>;
+
+/// Why the pool rejected a submitted share, as reported by `SubmitSharesError`. Public because
+/// it's exposed through `ChannelStatsSnapshot::rejected_by_reason` for consumers outside this
+/// module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectReason {
+    StaleShare,
+    LowDifficulty,
+    InvalidNonce,
+    Other,
+}
+
+impl From<&str> for RejectReason {
+    fn from(code: &str) -> Self {
+        match code {
+            "stale-share" => Self::StaleShare,
+            "difficulty-too-low" => Self::LowDifficulty,
+            "invalid-nonce" => Self::InvalidNonce,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// 2^128, used to reassemble a 256-bit value into an `f64` from its high/low 128-bit halves.
+const TWO_POW_128: f64 = 340_282_366_920_938_463_463_374_607_431_768_211_456.0;
+
+/// Difficulty-1 target as used throughout Bitcoin/Stratum, i.e. the target that corresponds to
+/// a mining difficulty of exactly 1: `0xffff` shifted left by 208 bits. Its significant bits sit
+/// above bit 128, so - like `SetTarget.max_target` below - it doesn't fit in a `u128` and is kept
+/// as a high/low pair instead.
+const DIFF1_TARGET_HIGH: u128 = 0xffffu128 << 80;
+const DIFF1_TARGET_LOW: u128 = 0;
+
+/// Converts a compact `nBits` value (as carried by `SetNewPrevHash`) into a difficulty.
+fn bits_to_difficulty(bits: u32) -> f64 {
+    let exponent = (bits >> 24) as i32;
+    let mantissa = f64::from(bits & 0x00ff_ffff);
+    if mantissa == 0.0 {
+        return 0.0;
+    }
+    const DIFF1_EXPONENT: i32 = 0x1d;
+    const DIFF1_MANTISSA: f64 = 0x00ff_ff as f64;
+    (DIFF1_MANTISSA / mantissa) * 256f64.powi(DIFF1_EXPONENT - exponent)
+}
+
+/// Converts a raw 256-bit share target (as carried by `SetTarget`) into a difficulty.
+/// `target_high`/`target_low` are the upper/lower 128 bits of the target: a target this size
+/// doesn't fit in a single `u128`, and truncating it down to its low 128 bits (as an earlier
+/// version of this code did) silently returns a difficulty of 0 for any realistic vardiff
+/// target, whose significant bits sit in the upper half.
+fn target_to_difficulty(target_high: u128, target_low: u128) -> f64 {
+    if target_high == 0 && target_low == 0 {
+        return 0.0;
+    }
+    let target = target_high as f64 * TWO_POW_128 + target_low as f64;
+    let diff1_target = DIFF1_TARGET_HIGH as f64 * TWO_POW_128 + DIFF1_TARGET_LOW as f64;
+    diff1_target / target
+}
+
+#[derive(Debug, Clone, Default)]
+struct ChannelStats {
+    shares_submitted: u64,
+    shares_accepted: u64,
+    shares_rejected: u64,
+    rejected_by_reason: HashMap<RejectReason, u64>,
+    /// Which pool gets credit for an accepted share, keyed by `PoolConfig::address` - only one
+    /// pool is ever active at a time, but this survives failovers within the channel's lifetime.
+    accepted_by_pool: HashMap<String, u64>,
+    last_submit_at: Option<Instant>,
+    last_accepted_share_at: Option<Instant>,
+}
+
+/// Cheap, read-only copy of a channel's stats for consumers outside the client task.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelStatsSnapshot {
+    pub shares_submitted: u64,
+    pub shares_accepted: u64,
+    pub shares_rejected: u64,
+    pub rejected_by_reason: HashMap<RejectReason, u64>,
+    pub accepted_by_pool: HashMap<String, u64>,
+    pub seconds_since_last_accepted_share: Option<f64>,
+}
+
+/// Cheap, read-only copy of the whole connection's stats for consumers outside the client task.
+#[derive(Debug, Clone, Default)]
+pub struct StratumStatsSnapshot {
+    pub block_height: u32,
+    pub network_difficulty: f64,
+    pub estimated_hashrate: f64,
+    pub channels: HashMap<u32, ChannelStatsSnapshot>,
+}
+
+#[derive(Default)]
+struct StratumStatsInner {
+    block_height: u32,
+    network_difficulty: f64,
+    share_difficulty: f64,
+    /// Fed by `HashRateEstimator`, which actually owns the accepted-share bookkeeping used to
+    /// derive it; kept here purely so a snapshot can report it alongside everything else.
+    estimated_hashrate: f64,
+    channels: HashMap<u32, ChannelStats>,
+}
+
+impl StratumStatsInner {
+    fn channel(&mut self, channel_id: u32) -> &mut ChannelStats {
+        self.channels.entry(channel_id).or_default()
+    }
+}
+
+/// Live health of the stratum connection: accepted/rejected shares, estimated local hashrate
+/// and block/network info, per channel and aggregated. Cheaply cloneable so a UI or metrics
+/// task can poll it from outside the client task.
+#[derive(Clone, Default)]
+pub struct StratumStats {
+    inner: Arc<Mutex<StratumStatsInner>>,
+}
+
+impl StratumStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn set_block_height(&self, block_height: u32) {
+        self.inner.lock().expect("stats lock poisoned").block_height = block_height;
+    }
+
+    fn set_network_difficulty_from_bits(&self, bits: u32) {
+        self.inner
+            .lock()
+            .expect("stats lock poisoned")
+            .network_difficulty = bits_to_difficulty(bits);
+    }
+
+    fn set_share_difficulty(&self, difficulty: f64) {
+        self.inner
+            .lock()
+            .expect("stats lock poisoned")
+            .share_difficulty = difficulty;
+    }
+
+    /// The share (vardiff) difficulty last set by `SetTarget`, used by `HashRateEstimator` to
+    /// turn an accepted-share count into a hashrate.
+    fn share_difficulty(&self) -> f64 {
+        self.inner.lock().expect("stats lock poisoned").share_difficulty
+    }
+
+    /// Called by `StratumSolutionHandler` after every submit with `HashRateEstimator`'s current
+    /// estimate, so a snapshot can report it.
+    fn set_estimated_hashrate(&self, estimated_hashrate: f64) {
+        self.inner
+            .lock()
+            .expect("stats lock poisoned")
+            .estimated_hashrate = estimated_hashrate;
+    }
+
+    fn record_submit(&self, channel_id: u32) {
+        let mut inner = self.inner.lock().expect("stats lock poisoned");
+        inner.channel(channel_id).shares_submitted += 1;
+        inner.channel(channel_id).last_submit_at = Some(Instant::now());
+    }
+
+    fn record_accept(&self, channel_id: u32, pool_address: &str) {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().expect("stats lock poisoned");
+        let channel = inner.channel(channel_id);
+        channel.shares_accepted += 1;
+        channel.last_accepted_share_at = Some(now);
+        *channel
+            .accepted_by_pool
+            .entry(pool_address.to_string())
+            .or_insert(0) += 1;
+    }
+
+    fn record_reject(&self, channel_id: u32, reason: RejectReason) {
+        let mut inner = self.inner.lock().expect("stats lock poisoned");
+        let channel = inner.channel(channel_id);
+        channel.shares_rejected += 1;
+        *channel.rejected_by_reason.entry(reason).or_insert(0) += 1;
+    }
+
+    /// Takes a consistent, owned snapshot of the current stats. Reports the hashrate as zero
+    /// until at least one share has been accepted, rather than showing the seeded initial guess.
+    pub fn snapshot(&self) -> StratumStatsSnapshot {
+        let now = Instant::now();
+        let inner = self.inner.lock().expect("stats lock poisoned");
+        let any_accepted = inner.channels.values().any(|c| c.shares_accepted > 0);
+        let estimated_hashrate = if any_accepted {
+            inner.estimated_hashrate
+        } else {
+            0.0
+        };
+
+        StratumStatsSnapshot {
+            block_height: inner.block_height,
+            network_difficulty: inner.network_difficulty,
+            estimated_hashrate,
+            channels: inner
+                .channels
+                .iter()
+                .map(|(&channel_id, channel)| {
+                    (
+                        channel_id,
+                        ChannelStatsSnapshot {
+                            shares_submitted: channel.shares_submitted,
+                            shares_accepted: channel.shares_accepted,
+                            shares_rejected: channel.shares_rejected,
+                            rejected_by_reason: channel.rejected_by_reason.clone(),
+                            accepted_by_pool: channel.accepted_by_pool.clone(),
+                            seconds_since_last_accepted_share: channel
+                                .last_accepted_share_at
+                                .map(|at| now.duration_since(at).as_secs_f64()),
+                        },
+                    )
+                })
+                .collect(),
+        }
     }
 }
 
+/// How often the nominal hashrate reported to the pool is reconsidered.
+const HASHRATE_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+/// Minimum relative change from what was last reported before bothering to re-announce it.
+const HASHRATE_REPORT_THRESHOLD: f64 = 0.2;
+/// Exponential moving average smoothing factor for new samples; closer to 1 reacts faster.
+const HASHRATE_EMA_ALPHA: f64 = 0.3;
+/// What `open_channel` seeds the pool with before any shares have been accepted.
+const INITIAL_NOMINAL_HASHRATE: f64 = 1e9;
+
+/// Client-side estimate of our hashrate, derived from accepted shares, used to keep the pool's
+/// vardiff targeting close to our actual throughput. Persists across reconnects so a dropped
+/// connection doesn't reset the pool back to its initial, wildly wrong guess.
+struct HashRateEstimator {
+    estimate: f64,
+    last_reported: f64,
+    accepted_since_update: u64,
+    last_update_at: Instant,
+}
+
+impl HashRateEstimator {
+    fn new() -> Self {
+        Self {
+            estimate: INITIAL_NOMINAL_HASHRATE,
+            last_reported: INITIAL_NOMINAL_HASHRATE,
+            accepted_since_update: 0,
+            last_update_at: Instant::now(),
+        }
+    }
+
+    fn record_accept(&mut self) {
+        self.accepted_since_update += 1;
+    }
+
+    fn estimate(&self) -> f64 {
+        self.estimate
+    }
+
+    /// Folds in shares accepted since the last recompute; does nothing until
+    /// `HASHRATE_UPDATE_INTERVAL` has passed and at least one share has been accepted since
+    /// then. Returns `Some(hashrate)` when the result
+    /// has drifted far enough from what was last reported to the pool to be worth re-announcing.
+    fn maybe_report(&mut self, share_difficulty: f64, now: Instant) -> Option<f64> {
+        let elapsed = now.duration_since(self.last_update_at);
+        if elapsed < HASHRATE_UPDATE_INTERVAL || self.accepted_since_update == 0 {
+            return None;
+        }
+
+        let sample = self.accepted_since_update as f64 * share_difficulty * (1u64 << 32) as f64
+            / elapsed.as_secs_f64();
+        self.estimate = HASHRATE_EMA_ALPHA * sample + (1.0 - HASHRATE_EMA_ALPHA) * self.estimate;
+        self.accepted_since_update = 0;
+        self.last_update_at = now;
+
+        let drift = (self.estimate - self.last_reported).abs() / self.last_reported.max(1.0);
+        if drift >= HASHRATE_REPORT_THRESHOLD {
+            self.last_reported = self.estimate;
+            Some(self.estimate)
+        } else {
+            None
+        }
+    }
+}
+
+/// Shared between `StratumEventHandler`, which feeds it accepted shares, and
+/// `StratumSolutionHandler`, which periodically recomputes it and reports drift to the pool.
+type SharedHashRateEstimator = Arc<Mutex<HashRateEstimator>>;
+
 struct StratumEventHandler {
     job_sender: workhub::JobSender,
     new_jobs: HashMap<u32, NewMiningJob>,
     current_block_height: Arc<AtomicU32>,
+    in_flight_shares: SharedInFlightShares,
+    hashrate_estimator: SharedHashRateEstimator,
+    stats: StratumStats,
+    /// Version-rolling mask granted by the pool during connection setup; zero if it doesn't
+    /// support version rolling. Threaded into every `StratumJob` created from here on.
+    version_rolling_mask: u32,
+    /// Address of the pool this connection was made to, so accepted shares can be credited to
+    /// the right entry in `StratumStats` when `run` is failing over between several pools.
+    active_pool: String,
+    /// Shares rejected back to back, with no accepted share in between. Reset on every accept;
+    /// once it reaches `MAX_CONSECUTIVE_REJECTS` the pool is treated as unhealthy even though
+    /// the connection itself is still up, and `run` fails over to the next configured pool.
+    consecutive_rejects: u32,
+    /// Set by a visitor when it encounters a condition that should tear down the connection.
+    /// `V2Handler` visitors don't return `Result`, so errors are recorded here and picked up by
+    /// the caller after each accepted message.
+    error: Option<ConnectionError>,
 }
 
 impl StratumEventHandler {
-    pub fn new(job_sender: workhub::JobSender) -> Self {
+    pub fn new(
+        job_sender: workhub::JobSender,
+        in_flight_shares: SharedInFlightShares,
+        hashrate_estimator: SharedHashRateEstimator,
+        stats: StratumStats,
+        version_rolling_mask: u32,
+        active_pool: String,
+    ) -> Self {
         Self {
             job_sender,
             new_jobs: Default::default(),
             current_block_height: Arc::new(AtomicU32::new(0)),
+            in_flight_shares,
+            hashrate_estimator,
+            stats,
+            version_rolling_mask,
+            active_pool,
+            consecutive_rejects: 0,
+            error: None,
         }
     }
+
+    /// Takes the error recorded by the last visited message, if any.
+    fn take_error(&mut self) -> Option<ConnectionError> {
+        self.error.take()
+    }
 }
 
 impl V2Handler for StratumEventHandler {
@@ -125,13 +643,25 @@ impl V2Handler for StratumEventHandler {
         // immediately update current block height which is propagated to currently solved jobs
         self.current_block_height
             .store(current_block_height, Ordering::Relaxed);
+        self.stats.set_block_height(current_block_height);
+        self.stats
+            .set_network_difficulty_from_bits(prevhash_msg.nbits);
         // find a job with the same block height as provided in previous hash
         if let Some((_, job_msg)) = self.new_jobs.remove_entry(&current_block_height) {
-            let job = StratumJob::new(&job_msg, prevhash_msg, self.current_block_height.clone());
+            let job = StratumJob::new(
+                &job_msg,
+                prevhash_msg,
+                self.current_block_height.clone(),
+                self.version_rolling_mask,
+            );
             self.job_sender.send(Arc::new(job));
         } else {
-            // TODO: close connection when any job with provided block height hasn't been found
-            panic!("cannot find any job for current block height");
+            // cannot recover locally - bail out of the event loop and let the reconnect
+            // supervisor establish a fresh connection
+            self.error = Some(ConnectionError::Protocol(
+                "cannot find any job for current block height".to_string(),
+            ));
+            return;
         }
 
         // remove all jobs with lower block height
@@ -140,29 +670,106 @@ impl V2Handler for StratumEventHandler {
     }
 
     fn visit_set_target(&mut self, _msg: &Message<V2Protocol>, target_msg: &SetTarget) {
+        self.stats.set_share_difficulty(target_to_difficulty(
+            (target_msg.max_target >> 128u32).low_u128(),
+            target_msg.max_target.low_u128(),
+        ));
         self.job_sender.change_target(target_msg.max_target.into());
     }
+
+    fn visit_submit_shares_success(
+        &mut self,
+        _msg: &Message<V2Protocol>,
+        success_msg: &SubmitSharesSuccess,
+    ) {
+        // the pool acknowledges shares cumulatively, so everything up to `last_seq_num` is done
+        let accepted = self
+            .in_flight_shares
+            .lock()
+            .expect("in-flight shares lock poisoned")
+            .remove_up_to(success_msg.last_seq_num);
+        if !accepted.is_empty() {
+            let mut estimator = self
+                .hashrate_estimator
+                .lock()
+                .expect("hashrate estimator lock poisoned");
+            for _ in 0..accepted.len() {
+                estimator.record_accept();
+            }
+        }
+        if !accepted.is_empty() {
+            self.consecutive_rejects = 0;
+        }
+        for job in accepted {
+            self.stats.record_accept(job.channel_id, &self.active_pool);
+        }
+    }
+
+    fn visit_submit_shares_error(
+        &mut self,
+        _msg: &Message<V2Protocol>,
+        error_msg: &SubmitSharesError,
+    ) {
+        let job = self
+            .in_flight_shares
+            .lock()
+            .expect("in-flight shares lock poisoned")
+            .remove(error_msg.seq_num);
+        let reason = RejectReason::from(error_msg.code.as_str());
+        self.stats.record_reject(error_msg.channel_id, reason);
+
+        match job {
+            Some(job) if reason == RejectReason::StaleShare => {
+                // no point waiting for the next SetNewPrevHash - the pool already told us this
+                // job is dead
+                job.invalidate();
+            }
+            Some(_) | None => {
+                // low difficulty, invalid nonce, etc. - nothing to do locally beyond the stats
+                // this feeds; the job (if still tracked) is still valid
+            }
+        }
+
+        self.consecutive_rejects += 1;
+        if self.consecutive_rejects >= MAX_CONSECUTIVE_REJECTS {
+            // the connection is fine, but the pool itself looks unhealthy - let the failover
+            // supervisor try the next configured pool instead of hammering this one forever
+            self.error = Some(ConnectionError::TooManyRejects);
+        }
+    }
 }
 
-struct StratumSolutionHandler {
+struct StratumSolutionHandler<'a> {
     connection_tx: ConnectionTx<V2Framing>,
-    job_solution: workhub::JobSolutionReceiver,
+    job_solution: &'a mut workhub::JobSolutionReceiver,
+    in_flight_shares: SharedInFlightShares,
+    hashrate_estimator: SharedHashRateEstimator,
+    stats: StratumStats,
     seq_num: u32,
 }
 
-impl StratumSolutionHandler {
+impl<'a> StratumSolutionHandler<'a> {
     fn new(
         connection_tx: ConnectionTx<V2Framing>,
-        job_solution: workhub::JobSolutionReceiver,
+        job_solution: &'a mut workhub::JobSolutionReceiver,
+        in_flight_shares: SharedInFlightShares,
+        hashrate_estimator: SharedHashRateEstimator,
+        stats: StratumStats,
     ) -> Self {
         Self {
             connection_tx,
             job_solution,
+            in_flight_shares,
+            hashrate_estimator,
+            stats,
             seq_num: 0,
         }
     }
 
-    async fn process_solution(&mut self, solution: hal::UniqueMiningWorkSolution) {
+    async fn process_solution(
+        &mut self,
+        solution: hal::UniqueMiningWorkSolution,
+    ) -> Result<(), ConnectionError> {
         let job: &StratumJob = solution.job();
 
         let seq_num = self.seq_num;
@@ -176,30 +783,76 @@ impl StratumSolutionHandler {
             ntime_offset: solution.time_offset(),
             version: solution.version(),
         };
-        // send solutions back to the stratum server
-        await!(ConnectionTx::send(&mut self.connection_tx, share_msg))
-            .expect("Cannot send submit to stratum server");
-        // the response is handled in a separate task
+        // remember this submission so the success/error visitors can match it up once the
+        // pool's response comes back on the connection_rx side
+        self.in_flight_shares
+            .lock()
+            .expect("in-flight shares lock poisoned")
+            .insert(seq_num, job.clone());
+        self.stats.record_submit(job.channel_id);
+
+        // send solutions back to the stratum server; the response is handled by
+        // StratumEventHandler in a separate task
+        await!(ConnectionTx::send(&mut self.connection_tx, share_msg))?;
+
+        // opportunistically reconsider our reported hashrate on the back of every submit rather
+        // than running a separate timer task
+        let report = {
+            let mut estimator = self
+                .hashrate_estimator
+                .lock()
+                .expect("hashrate estimator lock poisoned");
+            let report = estimator.maybe_report(self.stats.share_difficulty(), Instant::now());
+            self.stats.set_estimated_hashrate(estimator.estimate());
+            report
+        };
+        if let Some(nominal_hashrate) = report {
+            await!(update_nominal_hashrate(
+                &mut self.connection_tx,
+                job.channel_id,
+                nominal_hashrate
+            ))?;
+        }
+
+        Ok(())
     }
 
-    async fn run(mut self) {
-        while let Some(solution) = await!(self.job_solution.receive()) {
-            await!(self.process_solution(solution));
+    /// Drives solution submission until the job solver closes or `shutdown` is signalled by the
+    /// reconnect supervisor.
+    async fn run(mut self, mut shutdown: watch::Receiver<bool>) -> Result<(), ConnectionError> {
+        loop {
+            match await!(future::select(self.job_solution.receive(), shutdown.next())) {
+                Either::Left((Some(solution), _)) => await!(self.process_solution(solution))?,
+                Either::Left((None, _)) => return Ok(()),
+                Either::Right(_) => return Ok(()),
+            }
         }
     }
 }
 
-struct StratumConnectionHandler(Result<(), ()>);
+struct StratumConnectionHandler {
+    result: Result<(), ConnectionError>,
+    /// Populated from `SetupMiningConnectionSuccess` - `None` if the pool didn't grant (or
+    /// doesn't support) version rolling.
+    version_rolling_mask: Option<u32>,
+}
 
 impl StratumConnectionHandler {
     fn new() -> Self {
-        Self(Err(()))
+        Self {
+            result: Err(ConnectionError::Protocol(
+                "no setup/open-channel response received".to_string(),
+            )),
+            version_rolling_mask: None,
+        }
     }
 
-    fn visit(response_msg: <V2Framing as Framing>::Receive) -> Result<(), ()> {
+    fn visit(
+        response_msg: <V2Framing as Framing>::Receive,
+    ) -> (Result<(), ConnectionError>, Option<u32>) {
         let mut handler = Self::new();
         response_msg.accept(&mut handler);
-        handler.0
+        (handler.result, handler.version_rolling_mask)
     }
 }
 
@@ -207,9 +860,10 @@ impl V2Handler for StratumConnectionHandler {
     fn visit_setup_mining_connection_success(
         &mut self,
         _msg: &Message<V2Protocol>,
-        _success_msg: &SetupMiningConnectionSuccess,
+        success_msg: &SetupMiningConnectionSuccess,
     ) {
-        self.0 = Ok(())
+        self.result = Ok(());
+        self.version_rolling_mask = success_msg.version_rolling_mask;
     }
 
     fn visit_setup_mining_connection_error(
@@ -217,7 +871,7 @@ impl V2Handler for StratumConnectionHandler {
         _msg: &Message<V2Protocol>,
         _error_msg: &SetupMiningConnectionError,
     ) {
-        self.0 = Err(())
+        self.result = Err(ConnectionError::SetupRejected)
     }
 
     fn visit_open_channel_success(
@@ -225,7 +879,7 @@ impl V2Handler for StratumConnectionHandler {
         _msg: &Message<V2Protocol>,
         _success_msg: &OpenChannelSuccess,
     ) {
-        self.0 = Ok(())
+        self.result = Ok(())
     }
 
     fn visit_open_channel_error(
@@ -233,28 +887,43 @@ impl V2Handler for StratumConnectionHandler {
         _msg: &Message<V2Protocol>,
         _error_msg: &OpenChannelError,
     ) {
-        self.0 = Err(())
+        self.result = Err(ConnectionError::OpenChannelRejected)
     }
 }
 
+/// Sets up the connection and negotiates version rolling, returning the mask the pool granted
+/// (zero if it doesn't support version rolling).
 async fn setup_mining_connection(
     connection: &mut Connection<V2Framing>,
     stratum_addr: String,
-) -> Result<(), ()> {
+) -> Result<u32, ConnectionError> {
     let setup_msg = SetupMiningConnection {
         protocol_version: 0,
         connection_url: stratum_addr,
         /// header only mining
         required_extranonce_size: 0,
+        // declare the version bits our hardware can roll; the pool grants back whichever
+        // subset (if any) it supports
+        version_rolling_mask: SUPPORTED_VERSION_ROLLING_MASK,
     };
-    await!(connection.send(setup_msg)).expect("Cannot send stratum setup mining connection");
-    let response_msg = await!(connection.next())
-        .expect("Cannot receive response for stratum setup mining connection")
-        .unwrap();
-    StratumConnectionHandler::visit(response_msg)
+    await!(connection.send(setup_msg))?;
+    let response_msg = await!(connection.next())?.ok_or_else(|| {
+        ConnectionError::Protocol("connection closed during setup mining connection".to_string())
+    })?;
+    let (result, version_rolling_mask) = StratumConnectionHandler::visit(response_msg);
+    result?;
+    Ok(version_rolling_mask.unwrap_or(0))
 }
 
-async fn open_channel(connection: &mut Connection<V2Framing>, user: String) -> Result<(), ()> {
+/// Maximum bitcoin target we declare support for, in compact `nBits` form: `0xffff << 208`
+/// (= difficulty 1 share). Shared between `open_channel` and `update_nominal_hashrate` so a
+/// later hashrate update re-announces the same capability instead of some other value.
+const MAX_TARGET_NBITS: u32 = 0x1d00ffff;
+
+async fn open_channel(
+    connection: &mut Connection<V2Framing>,
+    user: String,
+) -> Result<(), ConnectionError> {
     let channel_msg = OpenChannel {
         req_id: 10,
         user,
@@ -265,44 +934,348 @@ async fn open_channel(connection: &mut Connection<V2Framing>, user: String) -> R
             fw_ver: "Braiins OS 2019-06-05".to_string(),
             dev_id: "xyz".to_string(),
         },
-        nominal_hashrate: 1e9,
-        // Maximum bitcoin target is 0xffff << 208 (= difficulty 1 share)
-        max_target_nbits: 0x1d00ffff,
+        nominal_hashrate: INITIAL_NOMINAL_HASHRATE,
+        max_target_nbits: MAX_TARGET_NBITS,
         aggregated_device_count: 1,
     };
-    await!(connection.send(channel_msg)).expect("Cannot send stratum open channel");
-    let response_msg = await!(connection.next())
-        .expect("Cannot receive response for stratum open channel")
-        .unwrap();
-    StratumConnectionHandler::visit(response_msg)
+    await!(connection.send(channel_msg))?;
+    let response_msg = await!(connection.next())?.ok_or_else(|| {
+        ConnectionError::Protocol("connection closed during open channel".to_string())
+    })?;
+    let (result, _) = StratumConnectionHandler::visit(response_msg);
+    result
+}
+
+/// Re-announces our nominal hashrate to the pool so its vardiff targeting stays close to our
+/// actual throughput, instead of whatever we guessed (or it last heard) when the channel opened.
+async fn update_nominal_hashrate(
+    connection_tx: &mut ConnectionTx<V2Framing>,
+    channel_id: u32,
+    nominal_hashrate: f64,
+) -> Result<(), ConnectionError> {
+    let update_msg = UpdateChannel {
+        channel_id,
+        nominal_hashrate,
+        // re-send the same maximum target declared in open_channel - `Default::default()` would
+        // be zero, i.e. a target no share could ever satisfy, not "unchanged"
+        maximum_target: MAX_TARGET_NBITS,
+    };
+    await!(connection_tx.send(update_msg))?;
+    Ok(())
+}
+
+/// Resolves once a pending failback probe is due, or never if there isn't one (i.e. we're
+/// already on the highest-priority pool). Bundled into `event_handler_task`'s select loop so a
+/// long-lived lower-priority connection doesn't run forever without giving a higher-priority
+/// pool a chance to reclaim the work.
+async fn wait_for_failback_probe(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => await!(Delay::new(deadline)).expect("Timer failure"),
+        None => await!(future::pending::<()>()),
+    }
 }
 
+/// Reads messages off `connection_rx` and feeds them to `event_handler` until the connection is
+/// closed, a message triggers an unrecoverable error, `shutdown` is signalled, or
+/// `failback_deadline` elapses.
 async fn event_handler_task(
     mut connection_rx: ConnectionRx<V2Framing>,
     mut event_handler: StratumEventHandler,
-) {
-    while let Some(msg) = await!(connection_rx.next()) {
-        let msg = msg.unwrap();
-        msg.accept(&mut event_handler);
+    mut shutdown: watch::Receiver<bool>,
+    failback_deadline: Option<Instant>,
+) -> Result<(), ConnectionError> {
+    loop {
+        match await!(future::select(
+            connection_rx.next(),
+            future::select(shutdown.next(), wait_for_failback_probe(failback_deadline))
+        )) {
+            Either::Left((Some(msg), _)) => {
+                msg?.accept(&mut event_handler);
+                if let Some(err) = event_handler.take_error() {
+                    return Err(err);
+                }
+            }
+            Either::Left((None, _)) => {
+                return Err(ConnectionError::Protocol(
+                    "connection closed by stratum server".to_string(),
+                ))
+            }
+            Either::Right((Either::Left(_), _)) => return Ok(()),
+            Either::Right((Either::Right(_), _)) => return Err(ConnectionError::FailbackDue),
+        }
     }
 }
 
-pub async fn run(stratum_addr: String, user: String, job_solver: workhub::JobSolver) {
-    let socket_addr = stratum_addr.parse().expect("Invalid server address");
-    let (job_sender, job_solution) = job_solver.split();
+/// Sleeps for `duration`, used to back off between reconnect attempts.
+async fn backoff_sleep(duration: Duration) {
+    await!(Delay::new(Instant::now() + duration)).expect("Timer failure");
+}
 
-    let mut connection = await!(Connection::<V2Framing>::connect(&socket_addr))
-        .expect("Cannot connect to stratum server");
+/// Connects once, runs the connection to completion and reports why it ended. `job_sender` and
+/// `job_solution` are owned by the caller and outlive any number of calls to this function, so
+/// `workhub::JobSolver` stays alive across reconnects (and across failovers between pools).
+async fn run_once(
+    socket_addr: &std::net::SocketAddr,
+    pool: &PoolConfig,
+    job_sender: &workhub::JobSender,
+    job_solution: &mut workhub::JobSolutionReceiver,
+    stats: &StratumStats,
+    hashrate_estimator: &SharedHashRateEstimator,
+    backoff: &mut Duration,
+    failback_deadline: Option<Instant>,
+) -> Result<(), ConnectionError> {
+    let mut connection = await!(Connection::<V2Framing>::connect(socket_addr))?;
 
-    await!(setup_mining_connection(&mut connection, stratum_addr))
-        .expect("Cannot setup stratum mining connection");
-    await!(open_channel(&mut connection, user)).expect("Cannot open stratum channel");
+    let version_rolling_mask = await!(setup_mining_connection(
+        &mut connection,
+        pool.address.clone()
+    ))?;
+    await!(open_channel(&mut connection, pool.user.clone()))?;
+
+    // the channel is open - a fresh connection no longer deserves the backoff accumulated by
+    // previous failures
+    *backoff = INITIAL_RECONNECT_BACKOFF;
 
     let (connection_rx, connection_tx) = connection.split();
-    let event_handler = StratumEventHandler::new(job_sender);
+    let in_flight_shares: SharedInFlightShares = Arc::new(Mutex::new(InFlightShares::new()));
+    let event_handler = StratumEventHandler::new(
+        job_sender.clone(),
+        in_flight_shares.clone(),
+        hashrate_estimator.clone(),
+        stats.clone(),
+        version_rolling_mask,
+        pool.address.clone(),
+    );
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let (done_tx, done_rx) = oneshot::channel();
 
-    // run event handler in a separate task
-    tokio::spawn(event_handler_task(connection_rx, event_handler).compat_fix());
+    // run event handler in a separate task, report its outcome back through `done_tx`
+    tokio::spawn(
+        async move {
+            let result = await!(event_handler_task(
+                connection_rx,
+                event_handler,
+                shutdown_rx.clone(),
+                failback_deadline
+            ));
+            let _ = done_tx.send(result);
+        }
+            .compat_fix(),
+    );
 
-    await!(StratumSolutionHandler::new(connection_tx, job_solution).run());
-}
\ No newline at end of file
+    let solution_handler = StratumSolutionHandler::new(
+        connection_tx,
+        job_solution,
+        in_flight_shares,
+        hashrate_estimator.clone(),
+        stats.clone(),
+    );
+
+    // race the two tasks so a dead connection noticed by either side (e.g. the event-handler
+    // task detecting a dropped read) wakes the other one up immediately, rather than only
+    // signalling shutdown after the solution handler's own loop happens to return
+    let (solution_result, event_result) =
+        match await!(future::select(solution_handler.run(shutdown_rx), done_rx)) {
+            Either::Left((solution_result, done_rx)) => {
+                let _ = shutdown_tx.broadcast(true);
+                (solution_result, await!(done_rx).unwrap_or(Ok(())))
+            }
+            Either::Right((event_result, solution_fut)) => {
+                let _ = shutdown_tx.broadcast(true);
+                (await!(solution_fut), event_result.unwrap_or(Ok(())))
+            }
+        };
+
+    solution_result.and(event_result)
+}
+
+/// Runs the stratum client until the process is torn down, failing over between `pools` (highest
+/// priority first) as connections are lost or rejected. `stats` is owned jointly with the caller
+/// (e.g. a metrics endpoint), which can poll `StratumStats::snapshot` at any time, across any
+/// number of reconnects and failovers.
+pub async fn run(pools: Vec<PoolConfig>, job_solver: workhub::JobSolver, stats: StratumStats) {
+    let (job_sender, mut job_solution) = job_solver.split();
+    let hashrate_estimator: SharedHashRateEstimator = Arc::new(Mutex::new(HashRateEstimator::new()));
+
+    let mut failover = PoolFailover::new(pools);
+    let mut backoff = INITIAL_RECONNECT_BACKOFF;
+
+    loop {
+        if failover.should_attempt_failback(Instant::now()) {
+            failover.probe_failback();
+        }
+
+        let pool = failover.current().clone();
+        let socket_addr = match pool.address.parse() {
+            Ok(socket_addr) => socket_addr,
+            Err(err) => {
+                warn!(
+                    "invalid address for pool {} ({}), failing over",
+                    pool.address, err
+                );
+                failover.on_failure();
+                await!(backoff_sleep(backoff));
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                continue;
+            }
+        };
+
+        let result = await!(run_once(
+            &socket_addr,
+            &pool,
+            &job_sender,
+            &mut job_solution,
+            &stats,
+            &hashrate_estimator,
+            &mut backoff,
+            failover.failback_deadline(),
+        ));
+        match result {
+            Err(ConnectionError::FailbackDue) => {
+                // Not a failure - the connection was healthy, we just tore it down on purpose to
+                // give a higher-priority pool another chance. No backoff, no on_failure().
+                info!(
+                    "failback probe due, dropping connection to {} to retry a higher-priority pool",
+                    pool.address
+                );
+                failover.probe_failback();
+            }
+            Err(err) => {
+                warn!(
+                    "stratum connection to {} lost ({}), reconnecting in {:?}",
+                    pool.address, err, backoff
+                );
+                failover.on_failure();
+                await!(backoff_sleep(backoff));
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+            }
+            Ok(()) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_job(block_height: u32) -> StratumJob {
+        StratumJob {
+            id: 0,
+            channel_id: 0,
+            block_height,
+            current_block_height: Arc::new(AtomicU32::new(block_height)),
+            version: 0,
+            prev_hash: Hash::from_slice(&[0u8; 32]).unwrap(),
+            merkle_root: Hash::from_slice(&[0u8; 32]).unwrap(),
+            time: 0,
+            max_time: 0,
+            bits: 0,
+            version_mask: 0,
+            valid: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    #[test]
+    fn bits_to_difficulty_matches_known_values() {
+        // the genesis block's bits are difficulty 1 by definition
+        assert_eq!(bits_to_difficulty(0x1d00ffff), 1.0);
+        // a zero mantissa isn't a valid target - treat it as no difficulty rather than dividing
+        // by zero
+        assert_eq!(bits_to_difficulty(0x1d000000), 0.0);
+    }
+
+    #[test]
+    fn target_to_difficulty_matches_diff1_target() {
+        // the diff-1 target itself must come back as difficulty 1
+        assert_eq!(
+            target_to_difficulty(DIFF1_TARGET_HIGH, DIFF1_TARGET_LOW),
+            1.0
+        );
+        // a target of zero isn't meaningful - treat it as no difficulty rather than dividing by
+        // zero
+        assert_eq!(target_to_difficulty(0, 0), 0.0);
+        // a target twice as large as diff-1 halves the difficulty; this is exactly the
+        // regression the truncation bug hid, since the significant bits of a real vardiff
+        // target sit above bit 128 and used to be silently dropped by `low_u128()`
+        assert_eq!(
+            target_to_difficulty(DIFF1_TARGET_HIGH * 2, DIFF1_TARGET_LOW),
+            0.5
+        );
+    }
+
+    #[test]
+    fn in_flight_shares_remove_up_to_is_cumulative_and_ordered() {
+        let mut shares = InFlightShares::new();
+        shares.insert(1, test_job(1));
+        shares.insert(2, test_job(2));
+        shares.insert(3, test_job(3));
+
+        let acknowledged = shares.remove_up_to(2);
+        assert_eq!(
+            acknowledged.iter().map(|job| job.block_height).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        // seq_num 3 is still outstanding
+        assert!(shares.remove(3).is_some());
+    }
+
+    #[test]
+    fn in_flight_shares_evicts_oldest_beyond_capacity() {
+        let mut shares = InFlightShares::new();
+        for seq_num in 0..(MAX_IN_FLIGHT_SHARES as u32 + 1) {
+            shares.insert(seq_num, test_job(seq_num));
+        }
+        // the oldest entry was evicted to make room, everything after it is still tracked
+        assert!(shares.remove(0).is_none());
+        assert!(shares.remove(1).is_some());
+    }
+
+    #[test]
+    fn hashrate_estimator_suppresses_report_until_shares_land() {
+        let mut estimator = HashRateEstimator::new();
+        let start = Instant::now();
+
+        // interval elapsed, but nothing accepted yet - nothing to report
+        assert_eq!(estimator.maybe_report(1.0, start + HASHRATE_UPDATE_INTERVAL), None);
+
+        for _ in 0..200 {
+            estimator.record_accept();
+        }
+        let report = estimator.maybe_report(1.0, start + HASHRATE_UPDATE_INTERVAL);
+        assert!(report.is_some());
+        assert!(estimator.estimate() > INITIAL_NOMINAL_HASHRATE);
+    }
+
+    #[test]
+    fn pool_failover_advances_and_fails_back() {
+        let pools = vec![
+            PoolConfig {
+                address: "primary".to_string(),
+                user: "user".to_string(),
+                password: None,
+            },
+            PoolConfig {
+                address: "secondary".to_string(),
+                user: "user".to_string(),
+                password: None,
+            },
+        ];
+        let mut failover = PoolFailover::new(pools);
+        assert_eq!(failover.current().address, "primary");
+
+        failover.on_failure();
+        assert_eq!(failover.current().address, "secondary");
+        // wraps back to the top once every pool has failed
+        failover.on_failure();
+        assert_eq!(failover.current().address, "primary");
+
+        failover.on_failure();
+        let now = Instant::now();
+        assert!(!failover.should_attempt_failback(now));
+        assert!(failover.should_attempt_failback(now + FAILBACK_PROBE_INTERVAL));
+
+        failover.probe_failback();
+        assert_eq!(failover.current().address, "primary");
+    }
+}